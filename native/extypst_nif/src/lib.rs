@@ -2,19 +2,23 @@ use std::cell::{RefCell, RefMut};
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::hash::Hash;
+use std::io::Read as _;
 use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
 
+use chrono::{Datelike, Timelike};
 use comemo::Prehashed;
 use elsa::FrozenVec;
 use memmap2::Mmap;
 use once_cell::unsync::OnceCell;
+use rustler::ResourceArc;
 use same_file::Handle;
 use siphasher::sip128::{Hasher128, SipHasher13};
 use typst::{
     diag::{FileError, FileResult, StrResult},
     eval::{Bytes, Datetime, Library},
-    font::{Font, FontBook, FontInfo},
-    syntax::{FileId, Source, VirtualPath},
+    font::{Font, FontBook, FontInfo, FontStretch, FontStyle, FontVariant, FontWeight},
+    syntax::{FileId, PackageSpec, Source, VirtualPath},
     World,
 };
 use walkdir::WalkDir;
@@ -27,6 +31,7 @@ pub struct SystemWorld {
     fonts: Vec<FontSlot>,
     hashes: RefCell<HashMap<PathBuf, FileResult<PathHash>>>,
     paths: RefCell<HashMap<PathHash, PathSlot>>,
+    injected: RefCell<HashMap<VirtualPath, PathSlot>>,
     sources: FrozenVec<Box<Source>>,
     main: Source,
 }
@@ -39,6 +44,33 @@ struct FontSlot {
     font: OnceCell<Option<Font>>,
 }
 
+/// A discovered font face, described well enough for an Elixir caller to
+/// check whether a family resolves or to pick a specific variant.
+#[derive(Debug, Clone)]
+pub struct FontFace {
+    pub family: String,
+    pub style: String,
+    pub weight: u16,
+    pub stretch: u16,
+}
+
+impl FontFace {
+    fn new(family: &str, info: &FontInfo) -> Self {
+        let style = match info.variant.style {
+            FontStyle::Normal => "normal",
+            FontStyle::Italic => "italic",
+            FontStyle::Oblique => "oblique",
+        };
+
+        Self {
+            family: family.to_string(),
+            style: style.to_string(),
+            weight: info.variant.weight.to_number(),
+            stretch: (info.variant.stretch.to_ratio().get() * 1000.0).round() as u16,
+        }
+    }
+}
+
 /// Holds canonical data for all paths pointing to the same entity.
 #[derive(Default)]
 struct PathSlot {
@@ -60,15 +92,46 @@ impl World for SystemWorld {
     }
 
     fn source(&self, id: FileId) -> FileResult<Source> {
+        if let Some(source) = self.sources.iter().find(|&needle| needle.id().eq(&id)).cloned() {
+            return Ok(source);
+        }
+
+        if let Some(buffer) = self.injected.borrow().get(id.vpath()).and_then(|slot| slot.buffer.get().cloned()) {
+            let text = String::from_utf8(buffer?.to_vec()).map_err(|_| FileError::InvalidUtf8)?;
+            let source = Source::new(id, text);
+            self.sources.push(Box::new(source.clone()));
+            return Ok(source);
+        }
+
+        let path = self.system_path(id)?;
+        let mut slot = self.slot(&path)?;
+        let source_id = slot
+            .source
+            .get_or_init(|| {
+                let text = String::from_utf8(read(&path)?).map_err(|_| FileError::InvalidUtf8)?;
+                Ok(self.insert_at(id, text))
+            })
+            .clone()?;
+
         self.sources
             .iter()
-            .find(|&needle| needle.id().eq(&id))
+            .find(|&needle| needle.id().eq(&source_id))
             .cloned()
-            .ok_or_else(|| FileError::NotSource)
+            .ok_or(FileError::NotSource)
     }
 
     fn file(&self, id: FileId) -> FileResult<Bytes> {
-        Ok(Bytes::from(self.source(id)?.text().as_bytes()))
+        if let Some(buffer) = self.injected.borrow().get(id.vpath()).and_then(|slot| slot.buffer.get().cloned()) {
+            return buffer;
+        }
+
+        if let Ok(source) = self.source(id) {
+            return Ok(Bytes::from(source.text().as_bytes()));
+        }
+
+        let path = self.system_path(id)?;
+        let mut slot = self.slot(&path)?;
+        slot.buffer.get_or_init(|| read(&path).map(Bytes::from)).clone()
     }
 
     fn font(&self, id: usize) -> Option<Font> {
@@ -82,8 +145,23 @@ impl World for SystemWorld {
             .clone()
     }
 
-    fn today(&self, _offset: Option<i64>) -> Option<Datetime> {
-        unimplemented!()
+    fn today(&self, offset: Option<i64>) -> Option<Datetime> {
+        let now = match offset {
+            None => chrono::Local::now().naive_local(),
+            Some(hours) => {
+                let offset = chrono::FixedOffset::east_opt(i32::try_from(hours).ok()?.checked_mul(3600)?)?;
+                chrono::Utc::now().with_timezone(&offset).naive_local()
+            }
+        };
+
+        Datetime::from_ymd_hms(
+            now.year(),
+            now.month().try_into().ok()?,
+            now.day().try_into().ok()?,
+            now.hour().try_into().ok()?,
+            now.minute().try_into().ok()?,
+            now.second().try_into().ok()?,
+        )
     }
 }
 
@@ -106,6 +184,7 @@ impl SystemWorld {
             fonts: searcher.fonts,
             hashes: RefCell::default(),
             paths: RefCell::default(),
+            injected: RefCell::default(),
             sources: FrozenVec::new(),
             main: Source::detached("detached"),
         }
@@ -132,30 +211,88 @@ impl SystemWorld {
     }
 
     fn insert(&self, path: &Path, text: String) -> FileId {
-        let id = FileId::new(None, VirtualPath::new(path));
+        self.insert_at(FileId::new(None, VirtualPath::new(path)), text)
+    }
+
+    /// Makes an in-memory binary asset (or auxiliary source) available under
+    /// `path`, without it having to exist on disk.
+    fn insert_asset(&self, path: &Path, data: Vec<u8>) {
+        let vpath = VirtualPath::new(path);
+        let mut injected = self.injected.borrow_mut();
+        let slot = injected.entry(vpath).or_default();
+        let _ = slot.buffer.set(Ok(Bytes::from(data)));
+    }
+
+    fn insert_at(&self, id: FileId, text: String) -> FileId {
         let source = Source::new(id, text);
         self.sources.push(Box::new(source));
         id
     }
 
+    /// Resolves the on-disk location of a file, downloading and caching its
+    /// package first if the `FileId` points into one.
+    fn system_path(&self, id: FileId) -> FileResult<PathBuf> {
+        let root = match id.package() {
+            Some(spec) => prepare_package(spec).map_err(|msg| FileError::Other(Some(msg)))?,
+            None => self.root.clone(),
+        };
+
+        id.vpath().resolve(&root).ok_or(FileError::AccessDenied)
+    }
+
     fn reset(&mut self) {
         self.sources.as_mut().clear();
         self.hashes.borrow_mut().clear();
         self.paths.borrow_mut().clear();
+        self.injected.borrow_mut().clear();
     }
 
-    pub fn compile(&mut self, markup: String) -> StrResult<Vec<u8>> {
+    /// Lists every font family and variant discovered by this world.
+    pub fn font_faces(&self) -> Vec<FontFace> {
+        self.book
+            .families()
+            .flat_map(|(family, infos)| infos.map(move |info| FontFace::new(family, info)))
+            .collect()
+    }
+
+    /// Finds the face that best matches `family` and `variant`, using a
+    /// fontconfig-style nearest match: exact family (ignoring case), then closest weight by
+    /// absolute distance, then style, then stretch.
+    pub fn match_font(&self, family: &str, variant: FontVariant) -> Option<FontFace> {
+        // The book keys its families by their lowercased names.
+        let info = self
+            .book
+            .select_family(&family.to_lowercase())
+            .filter_map(|index| self.book.info(index))
+            .min_by_key(|info| {
+                (
+                    info.variant.weight.distance(variant.weight),
+                    info.variant.style.distance(variant.style),
+                    info.variant.stretch.distance(variant.stretch),
+                )
+            })?;
+        Some(FontFace::new(family, info))
+    }
+
+    pub fn compile(
+        &mut self,
+        markup: String,
+        extra_files: HashMap<String, Vec<u8>>,
+        format: OutputFormat,
+    ) -> StrResult<Vec<Vec<u8>>> {
         self.reset();
+
+        for (path, data) in extra_files {
+            self.insert_asset(Path::new(&path), data);
+        }
+
         self.main = self.source(self.insert(Path::new("MARKUP.tsp"), markup))?;
 
         let mut tracer = typst::eval::Tracer::new();
 
         match typst::compile(self, &mut tracer) {
-            // Export the PDF.
-            Ok(document) => {
-                let buffer = typst::export::pdf(&document, None, None);
-                Ok(buffer)
-            }
+            // Export the document in the requested format.
+            Ok(document) => export(&document, format),
 
             // Format diagnostics.
             Err(errors) => {
@@ -218,6 +355,118 @@ fn read(path: &Path) -> FileResult<Vec<u8>> {
     }
 }
 
+/// An output format a compiled document can be rendered to.
+pub enum OutputFormat {
+    Pdf,
+    Svg,
+    Png { pixel_per_pt: f32 },
+}
+
+/// Renders a compiled document, producing one buffer per page (a single
+/// buffer holding the whole file for PDF).
+fn export(document: &typst::doc::Document, format: OutputFormat) -> StrResult<Vec<Vec<u8>>> {
+    match format {
+        OutputFormat::Pdf => Ok(vec![typst::export::pdf(document, None, None)]),
+
+        OutputFormat::Svg => Ok(document
+            .pages
+            .iter()
+            .map(|frame| typst::export::svg(frame).into_bytes())
+            .collect()),
+
+        OutputFormat::Png { pixel_per_pt } => document
+            .pages
+            .iter()
+            .map(|frame| typst::export::render(frame, pixel_per_pt, typst::geom::Color::WHITE))
+            .map(|pixmap| {
+                pixmap
+                    .encode_png()
+                    .map_err(|e| format!("failed to encode page as PNG: {e}").into())
+            })
+            .collect(),
+    }
+}
+
+/// Returns the on-disk cache directory for a package, downloading and
+/// extracting it there first if it isn't already cached.
+fn prepare_package(spec: &PackageSpec) -> StrResult<PathBuf> {
+    let subdir = format!("typst/packages/{}/{}/{}", spec.namespace, spec.name, spec.version);
+
+    let dir = dirs::data_dir()
+        .ok_or_else(|| "failed to locate the system data directory".to_string())?
+        .join(&subdir);
+
+    if dir.exists() {
+        return Ok(dir);
+    }
+
+    if spec.namespace != "preview" {
+        return Err(format!(
+            "package {}/{}:{} is not cached and only the `preview` namespace can be downloaded",
+            spec.namespace, spec.name, spec.version
+        )
+        .into());
+    }
+
+    let url = format!("https://packages.typst.org/preview/{}-{}.tar.gz", spec.name, spec.version);
+
+    let mut builder = ureq::AgentBuilder::new();
+    if let Some(proxy_url) = env_proxy::for_url_str(&url).to_url() {
+        if let Ok(proxy) = ureq::Proxy::new(proxy_url.as_str()) {
+            builder = builder.proxy(proxy);
+        }
+    }
+
+    let response = builder
+        .build()
+        .get(&url)
+        .call()
+        .map_err(|e| format!("failed to download package {spec}: {e}"))?;
+
+    let mut archive = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut archive)
+        .map_err(|e| format!("failed to download package {spec}: {e}"))?;
+
+    let parent = dir
+        .parent()
+        .ok_or_else(|| format!("invalid package cache directory for {spec}"))?;
+    fs::create_dir_all(parent)
+        .map_err(|e| format!("failed to create package cache directory: {e}"))?;
+
+    // Extract into a sibling temp directory and rename into place so that
+    // concurrent extractions of the same package can never observe (or
+    // collide on) a half-written cache directory. The counter makes the
+    // temp directory unique per call, since concurrent NIF invocations
+    // share a single OS process.
+    static TMP_DIR_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let unique = TMP_DIR_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let tmp_dir = parent.join(format!(
+        ".{}-{}-{}-{}",
+        spec.name,
+        spec.version,
+        std::process::id(),
+        unique
+    ));
+    fs::create_dir_all(&tmp_dir).map_err(|e| format!("failed to create temp directory: {e}"))?;
+
+    tar::Archive::new(flate2::read::GzDecoder::new(archive.as_slice()))
+        .unpack(&tmp_dir)
+        .map_err(|e| format!("failed to extract package {spec}: {e}"))?;
+
+    match fs::rename(&tmp_dir, &dir) {
+        Ok(()) => {}
+        // Another process/thread already finished extracting it first.
+        Err(_) if dir.exists() => {
+            let _ = fs::remove_dir_all(&tmp_dir);
+        }
+        Err(e) => return Err(format!("failed to install package {spec}: {e}").into()),
+    }
+
+    Ok(dir)
+}
+
 /// Searches for fonts.
 struct FontSearcher {
     book: FontBook,
@@ -323,22 +572,122 @@ impl FontSearcher {
     }
 }
 
-#[rustler::nif]
-fn compile(markup: String, extra_fonts: Vec<String>) -> Result<String, String> {
+/// Resolves the `format`/`pixel_per_pt` NIF arguments into an `OutputFormat`.
+fn parse_format(format: &str, pixel_per_pt: f32) -> Result<OutputFormat, String> {
+    match format {
+        "pdf" => Ok(OutputFormat::Pdf),
+        "svg" => Ok(OutputFormat::Svg),
+        "png" => Ok(OutputFormat::Png { pixel_per_pt }),
+        other => Err(format!("unsupported output format: {other}")),
+    }
+}
+
+/// Wraps each page's raw bytes as an Elixir-bound binary.
+///
+/// The bytes are not necessarily utf-8 encoded, but this is exactly what we
+/// want as we are passing binaries back to Elixir.
+fn encode_pages(pages: Vec<Vec<u8>>) -> Vec<String> {
+    pages
+        .into_iter()
+        .map(|bytes| unsafe { String::from_utf8_unchecked(bytes) })
+        .collect()
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn compile(
+    markup: String,
+    extra_fonts: Vec<String>,
+    extra_files: HashMap<String, Vec<u8>>,
+    format: String,
+    pixel_per_pt: f32,
+) -> Result<Vec<String>, String> {
     let extra_fonts_paths: Vec<PathBuf> = extra_fonts.iter().map(|f| Path::new(f).into()).collect();
+    let format = parse_format(&format, pixel_per_pt)?;
 
     let mut world = SystemWorld::new(".".into(), extra_fonts_paths.as_slice(), &[]);
-    match world.compile(markup) {
-        Ok(pdf_bytes) => {
-            // the resulting string is not an utf-8 encoded string, but this is exactly what we
-            // want as we are passing a binary back to elixir
-            unsafe { Ok(String::from_utf8_unchecked(pdf_bytes)) }
-        }
+    match world.compile(markup, extra_files, format) {
+        Ok(pages) => Ok(encode_pages(pages)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A long-lived `SystemWorld` handle, so that font scanning and comemo's
+/// incremental-compilation caches are reused across NIF calls.
+pub struct WorldResource(Mutex<SystemWorld>);
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn new_world(extra_fonts: Vec<String>) -> ResourceArc<WorldResource> {
+    let extra_fonts_paths: Vec<PathBuf> = extra_fonts.iter().map(|f| Path::new(f).into()).collect();
+    let world = SystemWorld::new(".".into(), extra_fonts_paths.as_slice(), &[]);
+    ResourceArc::new(WorldResource(Mutex::new(world)))
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn compile_with(
+    world: ResourceArc<WorldResource>,
+    markup: String,
+    extra_files: HashMap<String, Vec<u8>>,
+    format: String,
+    pixel_per_pt: f32,
+) -> Result<Vec<String>, String> {
+    let format = parse_format(&format, pixel_per_pt)?;
+
+    let mut world = world.0.lock().map_err(|_| "the world handle is poisoned".to_string())?;
+    match world.compile(markup, extra_files, format) {
+        Ok(pages) => Ok(encode_pages(pages)),
         Err(e) => Err(e.into()),
     }
 }
 
-rustler::init!("Elixir.ExTypst.NIF", [compile]);
+/// A font face as handed back to Elixir: `{family, style, weight, stretch}`.
+type FontFaceTuple = (String, String, u16, u16);
+
+impl From<FontFace> for FontFaceTuple {
+    fn from(face: FontFace) -> Self {
+        (face.family, face.style, face.weight, face.stretch)
+    }
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn fonts(world: ResourceArc<WorldResource>) -> Result<Vec<FontFaceTuple>, String> {
+    let world = world.0.lock().map_err(|_| "the world handle is poisoned".to_string())?;
+    Ok(world.font_faces().into_iter().map(FontFaceTuple::from).collect())
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn match_font(
+    world: ResourceArc<WorldResource>,
+    family: String,
+    style: String,
+    weight: u16,
+    stretch: u16,
+) -> Result<Option<FontFaceTuple>, String> {
+    let style = match style.as_str() {
+        "italic" => FontStyle::Italic,
+        "oblique" => FontStyle::Oblique,
+        _ => FontStyle::Normal,
+    };
+
+    let variant = FontVariant {
+        style,
+        weight: FontWeight::from_number(weight),
+        stretch: FontStretch::from_ratio(typst::geom::Ratio::new(stretch as f64 / 1000.0)),
+    };
+
+    let world = world.0.lock().map_err(|_| "the world handle is poisoned".to_string())?;
+    Ok(world.match_font(&family, variant).map(FontFaceTuple::from))
+}
+
+fn load(env: rustler::Env, _info: rustler::Term) -> bool {
+    rustler::resource!(WorldResource, env);
+    true
+}
+
+rustler::init!(
+    "Elixir.ExTypst.NIF",
+    [compile, new_world, compile_with, fonts, match_font],
+    load = load
+);
 
 /// Normalizes a path such that that it can be used as a key in a hashmap.
 ///